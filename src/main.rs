@@ -2,20 +2,48 @@ use axum::{
     Router,
     body::{Body, Bytes},
     extract::Request,
-    http::{HeaderMap, StatusCode},
-    response::Response,
-    routing::any,
+    http::{HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{any, get},
 };
 use clap::Parser;
+use flate2::{Compression, write::GzEncoder};
 use futures::StreamExt;
-use http_body_util::BodyExt;
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
 use serde_json::{Value, json};
-use std::sync::OnceLock;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
 static HTTP_CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+static METRICS_HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+static RESPONSE_CACHE: OnceLock<Mutex<HashMap<String, CacheEntry>>> = OnceLock::new();
+
+/// A cached upstream response, keyed by method+path+query
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    content_type: Option<HeaderValue>,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    body: Bytes,
+    cached_at: Instant,
+    ttl: Duration,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        self.cached_at.elapsed() < self.ttl
+    }
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(name = "copilot-lmstudio-proxy")]
@@ -36,6 +64,43 @@ struct Config {
     /// Enable CORS (Cross-Origin Resource Sharing)
     #[arg(short, long, default_value_t = false)]
     cors: bool,
+
+    /// Path to a PEM-encoded CA certificate to trust when connecting to an HTTPS LMStudio upstream
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// Disable TLS certificate validation for the upstream connection (insecure, testing only)
+    #[arg(long, default_value_t = false)]
+    danger_accept_invalid_certs: bool,
+
+    /// Expose a GET /metrics endpoint with Prometheus text-format counters and histograms
+    #[arg(long, default_value_t = false)]
+    metrics: bool,
+
+    /// Cache GET responses for this many seconds (honoring upstream Cache-Control); 0 disables caching
+    #[arg(long, default_value_t = 0)]
+    cache_ttl: u64,
+
+    /// Maximum number of times to retry a request on connection errors or 5xx/429 responses
+    #[arg(long, default_value_t = 2)]
+    max_retries: u32,
+
+    /// Base delay between retries in milliseconds, doubled on each subsequent attempt
+    #[arg(long, default_value_t = 250)]
+    retry_backoff_ms: u64,
+
+    /// Maximum number of redirects to follow for the upstream request
+    #[arg(long, default_value_t = 5)]
+    max_redirects: usize,
+
+    /// OTLP gRPC endpoint to export traces to (e.g. http://localhost:4317)
+    #[arg(long, env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+
+    /// Maximum request body size, in bytes, that will be buffered in memory to parse
+    /// and fix a JSON payload; larger or non-JSON bodies are streamed straight through
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_buffer_bytes: u64,
 }
 
 #[tokio::main]
@@ -45,19 +110,32 @@ async fn main() {
     CONFIG.set(config.clone()).expect("Failed to set config");
 
     // Initialize HTTP client (reused for all requests for connection pooling)
-    let client = reqwest::Client::builder()
-        .http1_only() // LMStudio might not support HTTP/2
-        .build()
-        .expect("Failed to create HTTP client");
+    let client = create_http_client(&config).expect("Failed to create HTTP client");
     HTTP_CLIENT.set(client).expect("Failed to set HTTP client");
 
-    // Initialize tracing
+    // Initialize the response cache regardless of --cache-ttl; proxy_handler only
+    // populates and consults it when caching is actually enabled.
+    RESPONSE_CACHE
+        .set(Mutex::new(HashMap::new()))
+        .expect("Failed to set response cache");
+
+    // Trace-context propagation is wired up unconditionally (it's a no-op without a
+    // configured exporter) so an incoming traceparent header is always honored.
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // Initialize tracing, optionally layering in OTLP span export
+    let otel_layer = config.otlp_endpoint.as_ref().map(|endpoint| {
+        let tracer = init_otlp_tracer(endpoint);
+        tracing_opentelemetry::layer().with_tracer(tracer)
+    });
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
                 .unwrap_or_else(|_| "copilot_lmstudio_proxy=info,tower_http=debug".into()),
         )
         .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
         .init();
 
     let bind_addr = if config.bind_all {
@@ -72,9 +150,29 @@ async fn main() {
     if config.cors {
         info!("  CORS: enabled");
     }
+    if config.cache_ttl > 0 {
+        info!("  Cache: enabled ({}s TTL)", config.cache_ttl);
+    }
+    if let Some(endpoint) = &config.otlp_endpoint {
+        info!("  Tracing: OTLP export to {}", endpoint);
+    }
 
     let mut app = Router::new().fallback(any(proxy_handler));
 
+    // Install the Prometheus recorder and expose /metrics if requested. The handle is
+    // stored separately from the recorder so the route handler can render it on demand.
+    if config.metrics {
+        let handle = PrometheusBuilder::new()
+            .install_recorder()
+            .expect("Failed to install Prometheus recorder");
+        // PrometheusHandle doesn't implement Debug, so Result::expect isn't available here.
+        METRICS_HANDLE
+            .set(handle)
+            .unwrap_or_else(|_| panic!("Failed to set metrics handle"));
+        app = app.route("/metrics", get(metrics_handler));
+        info!("  Metrics: enabled on /metrics");
+    }
+
     // Add CORS layer if enabled
     if config.cors {
         use tower_http::cors::{Any, CorsLayer};
@@ -91,46 +189,216 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Builds the shared reqwest client, optionally trusting a custom CA
+fn create_http_client(config: &Config) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder = reqwest::Client::builder()
+        .http1_only() // LMStudio might not support HTTP/2
+        .use_rustls_tls()
+        .redirect(reqwest::redirect::Policy::limited(config.max_redirects));
+
+    if let Some(ca_path) = &config.ca_cert {
+        let ca_bytes = std::fs::read(ca_path)
+            .unwrap_or_else(|e| panic!("Failed to read CA cert file {:?}: {}", ca_path, e));
+        let cert =
+            reqwest::Certificate::from_pem(&ca_bytes).expect("Failed to parse CA cert as PEM");
+        builder = builder.add_root_certificate(cert);
+    }
+
+    if config.danger_accept_invalid_certs {
+        warn!("TLS certificate validation is disabled - do not use this in production");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build()
+}
+
+/// Builds an OTLP span exporter pipeline and registers it as the global tracer provider
+fn init_otlp_tracer(endpoint: &str) -> opentelemetry_sdk::trace::Tracer {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .expect("Failed to build OTLP span exporter");
+
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![
+            opentelemetry::KeyValue::new("service.name", "copilot-lmstudio-proxy"),
+        ]))
+        .build();
+
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    provider.tracer("copilot-lmstudio-proxy")
+}
+
+/// Renders the Prometheus text-format metrics snapshot
+async fn metrics_handler() -> impl IntoResponse {
+    let handle = METRICS_HANDLE.get().expect("Metrics handle not initialized");
+    handle.render()
+}
+
+/// Buckets a request path down to its first segment for low-cardinality metric labels
+fn metrics_path_prefix(path: &str) -> String {
+    match path.trim_start_matches('/').split('/').next() {
+        Some(segment) if !segment.is_empty() => format!("/{}", segment),
+        _ => "/".to_string(),
+    }
+}
+
+#[tracing::instrument(
+    name = "proxy_request",
+    skip_all,
+    fields(
+        method = tracing::field::Empty,
+        path = tracing::field::Empty,
+        upstream_status = tracing::field::Empty,
+        streaming = tracing::field::Empty,
+        body_fixes = tracing::field::Empty,
+    )
+)]
 async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
     let (parts, body) = req.into_parts();
     let method = parts.method.clone();
     let uri = parts.uri.clone();
     let path = uri.path();
     let query = uri.query().unwrap_or("");
+    let start = Instant::now();
+    let method_label = method.to_string();
+    let path_label = metrics_path_prefix(path);
+    // Reqwest strips this before forwarding (so LMStudio can't send us a compressed
+    // body), but we still want to know what the Copilot client itself can accept.
+    let client_accept_encoding = parts
+        .headers
+        .get("accept-encoding")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
 
-    info!(
-        "{} {} {}",
-        method,
-        path,
-        if query.is_empty() {
-            ""
-        } else {
-            &format!("?{}", query)
-        }
-    );
+    let span = tracing::Span::current();
+    span.record("method", method_label.as_str());
+    span.record("path", path_label.as_str());
 
-    // Read the original body
-    let body_bytes = match body.collect().await {
-        Ok(collected) => collected.to_bytes(),
-        Err(e) => {
-            error!("Failed to read request body: {}", e);
-            return Err(StatusCode::BAD_REQUEST);
-        }
+    // Join whatever trace the Copilot client is already part of, if any, instead of
+    // always starting a fresh one.
+    let parent_cx = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(&parts.headers))
+    });
+    span.set_parent(parent_cx);
+
+    metrics::counter!("proxy_requests_total", "method" => method_label.clone(), "path" => path_label.clone())
+        .increment(1);
+
+    let query_suffix = if query.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", query)
     };
+    info!("{} {} {}", method, path, query_suffix);
 
     let config = CONFIG.get().expect("Config not initialized");
 
-    // Try to parse and fix the body if it's JSON
-    let fixed_body_bytes = if !body_bytes.is_empty() && is_json_request(&parts.headers) {
-        match fix_request_body(&body_bytes) {
-            Ok(fixed) => fixed,
-            Err(e) => {
-                warn!("Could not fix request body: {}", e);
-                body_bytes
+    // Only bodies we actually need to parse are worth buffering: non-JSON bodies are
+    // never fixed, so stream them through unbuffered. A request with no body at all
+    // (the common case for GET) is never streamed, so it still goes through the
+    // buffered path below and keeps the benefit of send_with_retries.
+    let is_json_req = is_json_request(&parts.headers);
+    let content_length = parts
+        .headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    let has_body = content_length.is_some_and(|len| len > 0)
+        || parts.headers.contains_key("transfer-encoding");
+    let stream_passthrough = should_stream_request_body(is_json_req, has_body);
+
+    // Only GET requests are treated as cacheable; this is also what lets us safely
+    // skip the network entirely on a fresh hit further down.
+    let is_cacheable = config.cache_ttl > 0 && method == Method::GET;
+    let cache_key = cache_key(&method, path, query);
+    let mut stale_cache_entry = None;
+
+    if is_cacheable {
+        let cached = RESPONSE_CACHE
+            .get()
+            .expect("Response cache not initialized")
+            .lock()
+            .unwrap()
+            .get(&cache_key)
+            .cloned();
+
+        match cached {
+            Some(entry) if entry.is_fresh() => {
+                metrics::counter!("proxy_cache_lookups_total", "result" => "fresh_hit")
+                    .increment(1);
+                let response_headers = cache_entry_headers(&entry);
+                let response = build_response(
+                    StatusCode::OK,
+                    response_headers,
+                    entry.body,
+                    client_accept_encoding.as_deref(),
+                );
+                span.record("upstream_status", 200u16);
+                span.record("streaming", false);
+                span.record("body_fixes", 0u32);
+                metrics::histogram!("proxy_request_duration_seconds", "method" => method_label, "streaming" => "false")
+                    .record(start.elapsed().as_secs_f64());
+                return Ok(response);
+            }
+            Some(entry) => {
+                metrics::counter!("proxy_cache_lookups_total", "result" => "stale").increment(1);
+                stale_cache_entry = Some(entry);
+            }
+            None => {
+                metrics::counter!("proxy_cache_lookups_total", "result" => "miss").increment(1);
             }
         }
+    }
+
+    // Tracks how many bodies this request actually needed a compatibility fix for,
+    // surfaced on the span so fix-rates are visible without grepping logs.
+    let mut body_fix_count: u32 = 0;
+
+    // Stream non-JSON bodies straight through to the upstream instead of buffering
+    // them; only a JSON body gets read into memory so it can be parsed and fixed, and
+    // that read is capped against the bytes actually seen (not a client-declared
+    // Content-Length, which a chunked request is free to omit or understate).
+    let upstream_body: reqwest::Body = if stream_passthrough {
+        reqwest::Body::wrap_stream(body.into_data_stream())
     } else {
-        body_bytes
+        let body_bytes = match collect_body_with_limit(body, config.max_buffer_bytes).await {
+            Ok(bytes) => bytes,
+            Err(BodyReadError::TooLarge) => {
+                warn!(
+                    "Rejecting JSON request body exceeding {} bytes",
+                    config.max_buffer_bytes
+                );
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            Err(BodyReadError::Io(e)) => {
+                error!("Failed to read request body: {}", e);
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        };
+
+        let fixed_body_bytes = if !body_bytes.is_empty() {
+            match fix_request_body(&body_bytes) {
+                Ok((fixed, was_fixed)) => {
+                    if was_fixed {
+                        body_fix_count += 1;
+                        metrics::counter!("proxy_body_fixes_total", "stage" => "request")
+                            .increment(1);
+                    }
+                    fixed
+                }
+                Err(e) => {
+                    warn!("Could not fix request body: {}", e);
+                    body_bytes
+                }
+            }
+        } else {
+            body_bytes
+        };
+
+        fixed_body_bytes.into()
     };
 
     // Build the upstream URL
@@ -151,11 +419,16 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
         let name_str = name.as_str();
         // Skip host and headers that might cause issues. Reqwest recalculates
         // connection management, compression, and body length on our behalf.
+        // traceparent/tracestate are skipped here too since we inject our own below,
+        // reflecting this span as a hop in the trace rather than forwarding the
+        // client's raw context unchanged.
         if name_str == "host"
             || name_str.starts_with("sec-")
             || name_str == "connection"
             || name_str == "accept-encoding"
             || name_str == "content-length"
+            || name_str == "traceparent"
+            || name_str == "tracestate"
         {
             continue;
         }
@@ -163,14 +436,52 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
         upstream_req = upstream_req.header(name, value);
     }
 
+    // Inject the current span's context so the upstream hop (and LMStudio, if it
+    // understands traceparent) continues the same distributed trace.
+    let mut trace_headers = HeaderMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut HeaderInjector(&mut trace_headers));
+    });
+    for (name, value) in trace_headers.iter() {
+        upstream_req = upstream_req.header(name, value);
+    }
+
+    // Revalidate a stale cache entry instead of blindly re-fetching: if LMStudio
+    // still has nothing newer it'll answer 304 and we can replay the cached body.
+    if let Some(entry) = &stale_cache_entry {
+        if let Some(etag) = &entry.etag {
+            upstream_req = upstream_req.header("if-none-match", etag.clone());
+        }
+        if let Some(last_modified) = &entry.last_modified {
+            upstream_req = upstream_req.header("if-modified-since", last_modified.clone());
+        }
+    }
+
     // Add body
-    upstream_req = upstream_req.body(fixed_body_bytes);
+    upstream_req = upstream_req.body(upstream_body);
+
+    // Send request to LMStudio. A streamed body can only be sent once (the stream is
+    // consumed on the first attempt), so retries only apply to buffered requests.
+    let send_result = if stream_passthrough {
+        upstream_req.send().await
+    } else {
+        send_with_retries(
+            upstream_req,
+            &method,
+            config.max_retries,
+            Duration::from_millis(config.retry_backoff_ms),
+        )
+        .await
+    };
 
-    // Send request to LMStudio
-    let upstream_response = match upstream_req.send().await {
+    let upstream_response = match send_result {
         Ok(resp) => resp,
         Err(e) => {
             error!("Failed to proxy request: {}", e);
+            metrics::counter!("proxy_upstream_status_total", "method" => method_label.clone(), "status" => "error")
+                .increment(1);
+            metrics::histogram!("proxy_request_duration_seconds", "method" => method_label, "streaming" => "false")
+                .record(start.elapsed().as_secs_f64());
             return Err(StatusCode::BAD_GATEWAY);
         }
     };
@@ -181,6 +492,8 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
     if !status.is_success() {
         warn!("Response: {}", status);
     }
+    metrics::counter!("proxy_upstream_status_total", "method" => method_label.clone(), "status" => status.as_u16().to_string())
+        .increment(1);
 
     // Check if this is a streaming response
     let is_streaming = headers
@@ -188,6 +501,7 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
         .and_then(|v| v.to_str().ok())
         .map(|v| v.contains("text/event-stream"))
         .unwrap_or(false);
+    metrics::counter!("proxy_responses_total", "streaming" => is_streaming.to_string()).increment(1);
 
     // Strip hop-by-hop and encoding headers after reqwest's automatic decompression
     sanitize_response_headers(&mut headers);
@@ -197,7 +511,13 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
         let stream = upstream_response.bytes_stream();
         let fixed_stream = stream.map(move |chunk_result| match chunk_result {
             Ok(chunk) => match fix_streaming_chunk(&chunk) {
-                Ok(fixed) => Ok(fixed),
+                Ok(fixed) => {
+                    if fixed != chunk {
+                        metrics::counter!("proxy_body_fixes_total", "stage" => "streaming_chunk")
+                            .increment(1);
+                    }
+                    Ok(fixed)
+                }
                 Err(_) => Ok(chunk),
             },
             Err(e) => Err(std::io::Error::other(e)),
@@ -208,6 +528,40 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
         *response.status_mut() = status;
         *response.headers_mut() = headers;
 
+        span.record("upstream_status", status.as_u16());
+        span.record("streaming", true);
+        metrics::histogram!("proxy_request_duration_seconds", "method" => method_label, "streaming" => "true")
+            .record(start.elapsed().as_secs_f64());
+
+        Ok(response)
+    } else if status == StatusCode::NOT_MODIFIED && stale_cache_entry.is_some() {
+        // LMStudio confirmed our conditional request is still valid: replay the
+        // cached body and reset the freshness window instead of re-fetching it.
+        metrics::counter!("proxy_cache_lookups_total", "result" => "revalidated").increment(1);
+        let mut entry = stale_cache_entry.expect("checked above");
+        entry.cached_at = Instant::now();
+        let response_headers = cache_entry_headers(&entry);
+        let response_body = entry.body.clone();
+        RESPONSE_CACHE
+            .get()
+            .expect("Response cache not initialized")
+            .lock()
+            .unwrap()
+            .insert(cache_key.clone(), entry);
+
+        let response = build_response(
+            StatusCode::OK,
+            response_headers,
+            response_body,
+            client_accept_encoding.as_deref(),
+        );
+
+        span.record("upstream_status", 200u16);
+        span.record("streaming", false);
+        span.record("body_fixes", 0u32);
+        metrics::histogram!("proxy_request_duration_seconds", "method" => method_label, "streaming" => "false")
+            .record(start.elapsed().as_secs_f64());
+
         Ok(response)
     } else {
         // Handle non-streaming response
@@ -221,16 +575,53 @@ async fn proxy_handler(req: Request) -> Result<Response, StatusCode> {
 
         let fixed_body_bytes = if is_json_response(&headers) {
             match fix_response_body(&body_bytes) {
-                Ok(fixed) => fixed,
+                Ok((fixed, was_fixed)) => {
+                    if was_fixed {
+                        body_fix_count += 1;
+                        metrics::counter!("proxy_body_fixes_total", "stage" => "response")
+                            .increment(1);
+                    }
+                    fixed
+                }
                 Err(_) => body_bytes,
             }
         } else {
             body_bytes
         };
 
-        let mut response = Response::new(Body::from(fixed_body_bytes));
-        *response.status_mut() = status;
-        *response.headers_mut() = headers;
+        // Populate the cache for the next poll of this same idempotent endpoint,
+        // unless the upstream told us not to via Cache-Control: no-store.
+        if is_cacheable && status.is_success()
+            && let Some(ttl) = cacheable_ttl(&headers, Duration::from_secs(config.cache_ttl))
+        {
+            let entry = CacheEntry {
+                content_type: headers.get("content-type").cloned(),
+                etag: headers.get("etag").cloned(),
+                last_modified: headers.get("last-modified").cloned(),
+                body: fixed_body_bytes.clone(),
+                cached_at: Instant::now(),
+                ttl,
+            };
+            RESPONSE_CACHE
+                .get()
+                .expect("Response cache not initialized")
+                .lock()
+                .unwrap()
+                .insert(cache_key.clone(), entry);
+        }
+
+        let response = build_response(
+            status,
+            headers,
+            fixed_body_bytes,
+            client_accept_encoding.as_deref(),
+        );
+
+        span.record("upstream_status", status.as_u16());
+        span.record("streaming", false);
+        span.record("body_fixes", body_fix_count);
+        metrics::histogram!("proxy_request_duration_seconds", "method" => method_label, "streaming" => "false")
+            .record(start.elapsed().as_secs_f64());
 
         Ok(response)
     }
@@ -252,8 +643,37 @@ fn is_json_response(headers: &HeaderMap) -> bool {
         .unwrap_or(false)
 }
 
-fn fix_request_body(body: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>> {
+/// Whether the request body should be streamed through rather than buffered
+fn should_stream_request_body(is_json: bool, has_body: bool) -> bool {
+    has_body && !is_json
+}
+
+/// An error reading or capping a request body
+#[derive(Debug)]
+enum BodyReadError {
+    TooLarge,
+    Io(axum::Error),
+}
+
+/// Reads a request body into memory, enforcing `max_bytes` against the bytes actually seen
+async fn collect_body_with_limit(body: Body, max_bytes: u64) -> Result<Bytes, BodyReadError> {
+    let mut stream = body.into_data_stream();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(BodyReadError::Io)?;
+        if buffer.len() as u64 + chunk.len() as u64 > max_bytes {
+            return Err(BodyReadError::TooLarge);
+        }
+        buffer.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(buffer))
+}
+
+fn fix_request_body(body: &Bytes) -> Result<(Bytes, bool), Box<dyn std::error::Error>> {
     let mut json: Value = serde_json::from_slice(body)?;
+    let mut was_fixed = false;
 
     // Fix tools array (Issue #2)
     if let Some(tools) = json.get_mut("tools").and_then(|t| t.as_array_mut()) {
@@ -285,24 +705,24 @@ fn fix_request_body(body: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>> {
         if fixed_count > 0 {
             info!("Fixed {} tool parameter schema(s)", fixed_count);
         }
+        was_fixed = fixed_count > 0;
     }
 
-    Ok(Bytes::from(serde_json::to_vec(&json)?))
+    Ok((Bytes::from(serde_json::to_vec(&json)?), was_fixed))
 }
 
-fn fix_response_body(body: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>> {
+fn fix_response_body(body: &Bytes) -> Result<(Bytes, bool), Box<dyn std::error::Error>> {
     let mut json: Value = serde_json::from_slice(body)?;
+    let mut was_fixed = false;
 
     // Fix usage details (Issue #1)
     if let Some(usage) = json.get_mut("usage").and_then(|u| u.as_object_mut()) {
-        let mut fixed = false;
-
         if !usage.contains_key("input_tokens_details") {
             usage.insert(
                 "input_tokens_details".to_string(),
                 json!({"cached_tokens": 0}),
             );
-            fixed = true;
+            was_fixed = true;
         }
 
         if !usage.contains_key("output_tokens_details") {
@@ -310,15 +730,15 @@ fn fix_response_body(body: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>>
                 "output_tokens_details".to_string(),
                 json!({"reasoning_tokens": 0}),
             );
-            fixed = true;
+            was_fixed = true;
         }
 
-        if fixed {
+        if was_fixed {
             info!("Fixed usage details in response");
         }
     }
 
-    Ok(Bytes::from(serde_json::to_vec(&json)?))
+    Ok((Bytes::from(serde_json::to_vec(&json)?), was_fixed))
 }
 
 fn fix_streaming_chunk(chunk: &Bytes) -> Result<Bytes, Box<dyn std::error::Error>> {
@@ -374,6 +794,206 @@ fn fix_streaming_chunk(chunk: &Bytes) -> Result<Bytes, Box<dyn std::error::Error
     }
 }
 
+fn accepts_encoding(accept_encoding: &str, encoding: &str) -> bool {
+    for entry in accept_encoding.to_ascii_lowercase().split(',') {
+        let mut parts = entry.trim().split(';');
+        let Some(name) = parts.next().map(str::trim) else {
+            continue;
+        };
+        if name != encoding {
+            continue;
+        }
+
+        let q = parts
+            .map(str::trim)
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        return q > 0.0;
+    }
+    false
+}
+
+/// Picks the best encoding the client advertised, preferring brotli over gzip
+fn negotiate_encoding(accept_encoding: &str) -> Option<&'static str> {
+    if accepts_encoding(accept_encoding, "br") {
+        Some("br")
+    } else if accepts_encoding(accept_encoding, "gzip") {
+        Some("gzip")
+    } else {
+        None
+    }
+}
+
+fn compress_response_body(body: &Bytes, encoding: &str) -> std::io::Result<Bytes> {
+    use std::io::Write;
+
+    match encoding {
+        "gzip" => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            Ok(Bytes::from(encoder.finish()?))
+        }
+        "br" => {
+            let mut output = Vec::new();
+            {
+                let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                writer.write_all(body)?;
+            }
+            Ok(Bytes::from(output))
+        }
+        other => unreachable!("negotiate_encoding never returns {other}"),
+    }
+}
+
+/// HTTP methods safe to retry on a transient failure
+fn is_retryable_method(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET
+            | Method::HEAD
+            | Method::PUT
+            | Method::DELETE
+            | Method::OPTIONS
+            | Method::POST
+            | Method::PATCH
+    )
+}
+
+/// Upstream statuses worth retrying
+fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Exponential backoff: `base * 2^attempt`
+fn retry_backoff(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(1u32 << attempt.min(16))
+}
+
+/// Sends the upstream request, retrying transient failures up to `max_retries` times
+async fn send_with_retries(
+    request: reqwest::RequestBuilder,
+    method: &Method,
+    max_retries: u32,
+    backoff_base: Duration,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let retryable = is_retryable_method(method);
+    let mut attempt = 0;
+
+    loop {
+        let attempt_request = request
+            .try_clone()
+            .expect("upstream request body must be buffered and clonable");
+
+        match attempt_request.send().await {
+            Ok(resp)
+                if !retryable || attempt >= max_retries || !is_retryable_status(resp.status()) =>
+            {
+                return Ok(resp);
+            }
+            Ok(resp) => {
+                warn!(
+                    "Retrying after upstream returned {} (attempt {}/{})",
+                    resp.status(),
+                    attempt + 1,
+                    max_retries
+                );
+                metrics::counter!("proxy_retries_total", "reason" => "status").increment(1);
+            }
+            Err(e) if retryable && attempt < max_retries && (e.is_connect() || e.is_timeout()) => {
+                warn!(
+                    "Retrying after connection error: {} (attempt {}/{})",
+                    e,
+                    attempt + 1,
+                    max_retries
+                );
+                metrics::counter!("proxy_retries_total", "reason" => "connection").increment(1);
+            }
+            Err(e) => return Err(e),
+        }
+
+        tokio::time::sleep(retry_backoff(backoff_base, attempt)).await;
+        attempt += 1;
+    }
+}
+
+/// Builds the key under which a cacheable response is stored: method + path + query
+fn cache_key(method: &Method, path: &str, query: &str) -> String {
+    if query.is_empty() {
+        format!("{} {}", method, path)
+    } else {
+        format!("{} {}?{}", method, path, query)
+    }
+}
+
+/// Decides whether (and for how long) a response is cacheable, honoring `Cache-Control`
+fn cacheable_ttl(headers: &HeaderMap, default_ttl: Duration) -> Option<Duration> {
+    let Some(cache_control) = headers.get("cache-control").and_then(|v| v.to_str().ok()) else {
+        return Some(default_ttl);
+    };
+
+    let lower = cache_control.to_ascii_lowercase();
+    if lower.contains("no-store") {
+        return None;
+    }
+
+    for directive in lower.split(',') {
+        if let Some(max_age) = directive.trim().strip_prefix("max-age=")
+            && let Ok(secs) = max_age.parse::<u64>()
+        {
+            return Some(Duration::from_secs(secs));
+        }
+    }
+
+    Some(default_ttl)
+}
+
+/// Reconstructs the response headers a cache hit should carry
+fn cache_entry_headers(entry: &CacheEntry) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(content_type) = &entry.content_type {
+        headers.insert("content-type", content_type.clone());
+    }
+    if let Some(etag) = &entry.etag {
+        headers.insert("etag", etag.clone());
+    }
+    if let Some(last_modified) = &entry.last_modified {
+        headers.insert("last-modified", last_modified.clone());
+    }
+    headers
+}
+
+/// Applies client-requested compression (if any) and assembles the final `Response`
+fn build_response(
+    status: StatusCode,
+    mut headers: HeaderMap,
+    body_bytes: Bytes,
+    accept_encoding: Option<&str>,
+) -> Response {
+    let final_body_bytes = match accept_encoding.and_then(negotiate_encoding) {
+        Some(encoding) => match compress_response_body(&body_bytes, encoding) {
+            Ok(compressed) => {
+                headers.insert("content-encoding", HeaderValue::from_static(encoding));
+                headers.insert(
+                    "content-length",
+                    HeaderValue::from_str(&compressed.len().to_string()).unwrap(),
+                );
+                compressed
+            }
+            Err(e) => {
+                warn!("Failed to {} compress response body: {}", encoding, e);
+                body_bytes
+            }
+        },
+        None => body_bytes,
+    };
+
+    let mut response = Response::new(Body::from(final_body_bytes));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+}
+
 fn sanitize_response_headers(headers: &mut HeaderMap) {
     // These headers no longer reflect reality after reqwest decompressed the payload.
     headers.remove("content-encoding");
@@ -405,7 +1025,8 @@ mod tests {
         });
 
         let bytes = Bytes::from(serde_json::to_vec(&input).unwrap());
-        let fixed = fix_request_body(&bytes).expect("request body fix should succeed");
+        let (fixed, was_fixed) = fix_request_body(&bytes).expect("request body fix should succeed");
+        assert!(was_fixed);
         let fixed_json: Value = serde_json::from_slice(&fixed).unwrap();
         let tools = fixed_json["tools"]
             .as_array()
@@ -434,7 +1055,8 @@ mod tests {
         });
 
         let bytes = Bytes::from(serde_json::to_vec(&input).unwrap());
-        let fixed = fix_response_body(&bytes).expect("response body fix should succeed");
+        let (fixed, was_fixed) = fix_response_body(&bytes).expect("response body fix should succeed");
+        assert!(was_fixed);
         let fixed_json: Value = serde_json::from_slice(&fixed).unwrap();
         let usage = fixed_json["usage"].as_object().unwrap();
 
@@ -463,6 +1085,123 @@ mod tests {
         assert_eq!(fixed, chunk);
     }
 
+    #[test]
+    fn builds_default_client_without_ca_cert() {
+        let config = Config {
+            port: 3000,
+            lmstudio_url: "http://localhost:1234".to_string(),
+            bind_all: false,
+            cors: false,
+            ca_cert: None,
+            danger_accept_invalid_certs: false,
+            metrics: false,
+            cache_ttl: 0,
+            max_retries: 2,
+            retry_backoff_ms: 250,
+            max_redirects: 5,
+            otlp_endpoint: None,
+            max_buffer_bytes: 10 * 1024 * 1024,
+        };
+
+        assert!(create_http_client(&config).is_ok());
+    }
+
+    #[test]
+    fn buckets_path_to_first_segment() {
+        assert_eq!(metrics_path_prefix("/v1/chat/completions"), "/v1");
+        assert_eq!(metrics_path_prefix("/v1/models"), "/v1");
+        assert_eq!(metrics_path_prefix("/"), "/");
+    }
+
+    #[test]
+    fn negotiates_brotli_over_gzip() {
+        assert_eq!(negotiate_encoding("gzip, br"), Some("br"));
+        assert_eq!(negotiate_encoding("gzip"), Some("gzip"));
+        assert_eq!(negotiate_encoding("identity"), None);
+    }
+
+    #[test]
+    fn honors_a_zero_q_value_as_refused() {
+        assert_eq!(negotiate_encoding("br;q=0, gzip"), Some("gzip"));
+        assert_eq!(negotiate_encoding("br;q=0"), None);
+    }
+
+    #[test]
+    fn compresses_and_decompresses_gzip_roundtrip() {
+        let body = Bytes::from_static(b"{\"hello\":\"world\"}");
+        let compressed = compress_response_body(&body, "gzip").unwrap();
+        assert_ne!(compressed, body);
+
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body.to_vec());
+    }
+
+    #[test]
+    fn retryable_status_covers_5xx_and_429() {
+        assert!(is_retryable_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+        assert!(!is_retryable_status(StatusCode::OK));
+    }
+
+    #[test]
+    fn retry_backoff_doubles_each_attempt() {
+        let base = Duration::from_millis(100);
+        assert_eq!(retry_backoff(base, 0), Duration::from_millis(100));
+        assert_eq!(retry_backoff(base, 1), Duration::from_millis(200));
+        assert_eq!(retry_backoff(base, 3), Duration::from_millis(800));
+    }
+
+    #[test]
+    fn cache_key_includes_query_only_when_present() {
+        assert_eq!(cache_key(&Method::GET, "/v1/models", ""), "GET /v1/models");
+        assert_eq!(
+            cache_key(&Method::GET, "/v1/models", "verbose=1"),
+            "GET /v1/models?verbose=1"
+        );
+    }
+
+    #[test]
+    fn cacheable_ttl_honors_cache_control() {
+        let default_ttl = Duration::from_secs(30);
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(cacheable_ttl(&headers, default_ttl), Some(default_ttl));
+
+        headers.insert("cache-control", HeaderValue::from_static("no-store"));
+        assert_eq!(cacheable_ttl(&headers, default_ttl), None);
+
+        headers.insert("cache-control", HeaderValue::from_static("max-age=120"));
+        assert_eq!(
+            cacheable_ttl(&headers, default_ttl),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn cache_entry_tracks_freshness_against_its_ttl() {
+        let fresh = CacheEntry {
+            content_type: None,
+            etag: None,
+            last_modified: None,
+            body: Bytes::new(),
+            cached_at: Instant::now(),
+            ttl: Duration::from_secs(60),
+        };
+        assert!(fresh.is_fresh());
+
+        let stale = CacheEntry {
+            ttl: Duration::from_secs(0),
+            ..fresh
+        };
+        assert!(!stale.is_fresh());
+    }
+
     #[test]
     fn sanitizes_decompressed_headers() {
         let mut headers = HeaderMap::new();
@@ -478,4 +1217,36 @@ mod tests {
         assert!(headers.get("content-length").is_none());
         assert_eq!(headers.get("content-type").unwrap(), "application/json");
     }
+
+    #[test]
+    fn streams_non_json_bodies_that_have_content() {
+        assert!(should_stream_request_body(false, true));
+    }
+
+    #[test]
+    fn buffers_bodyless_requests_even_when_not_json() {
+        assert!(!should_stream_request_body(false, false));
+    }
+
+    #[test]
+    fn buffers_json_bodies_regardless_of_presence() {
+        assert!(!should_stream_request_body(true, true));
+        assert!(!should_stream_request_body(true, false));
+    }
+
+    #[tokio::test]
+    async fn collect_body_with_limit_rejects_bodies_over_the_cap() {
+        let body = Body::from(Bytes::from_static(b"0123456789"));
+        let result = collect_body_with_limit(body, 5).await;
+        assert!(matches!(result, Err(BodyReadError::TooLarge)));
+    }
+
+    #[tokio::test]
+    async fn collect_body_with_limit_allows_bodies_within_the_cap() {
+        let body = Body::from(Bytes::from_static(b"0123456789"));
+        let bytes = collect_body_with_limit(body, 10)
+            .await
+            .expect("body within the cap should be collected");
+        assert_eq!(bytes, Bytes::from_static(b"0123456789"));
+    }
 }